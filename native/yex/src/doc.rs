@@ -1,5 +1,6 @@
 use std::cell::RefCell;
 use std::ops::Deref;
+use std::time::{Duration, Instant};
 
 use crate::subscription::SubscriptionResource;
 use crate::wrap::encode_binary_slice_to_term;
@@ -7,19 +8,41 @@ use crate::xml::NifXmlFragment;
 use crate::{atoms, ENV};
 use rustler::{Binary, Env, LocalPid, NifStruct, NifUnitEnum, ResourceArc, Term};
 use yrs::updates::decoder::Decode;
-use yrs::updates::encoder::Encode;
+use yrs::updates::encoder::{Encode, EncoderV1};
 use yrs::*;
 
 use crate::{wrap::NifWrap, NifArray, NifError, NifMap, NifText};
 pub struct DocInner {
     pub doc: Doc,
     pub(crate) current_transaction: RefCell<Option<TransactionMut<'static>>>,
+    /// Pid that receives telemetry measurements emitted by [DocInner::mutably]/[DocInner::commit_transaction],
+    /// set via `doc_set_trace_pid`. `None` means telemetry is disabled for this document.
+    pub(crate) trace_pid: RefCell<Option<LocalPid>>,
+    pub(crate) transaction_started_at: RefCell<Option<Instant>>,
 }
 
 pub type DocResource = NifWrap<DocInner>;
 
 impl DocInner {
     pub fn mutably<F, T>(&self, env: Env<'_>, f: F) -> Result<T, NifError>
+    where
+        F: FnOnce(&mut TransactionMut<'_>) -> Result<T, NifError>,
+    {
+        self.mutably_with_origin(env, None, f)
+    }
+
+    /// Like [DocInner::mutably], but when no explicit transaction is already open (via
+    /// `doc_begin_transaction`), the one-shot transaction it creates is tagged with `origin` -
+    /// the same origin string `doc_begin_transaction` accepts - so `monitor_update_v1`
+    /// subscribers can tell these changes apart from untagged local edits. If an explicit
+    /// transaction is already open, `origin` is ignored and the open transaction is reused, same
+    /// as [DocInner::mutably].
+    pub fn mutably_with_origin<F, T>(
+        &self,
+        env: Env<'_>,
+        origin: Option<&str>,
+        f: F,
+    ) -> Result<T, NifError>
     where
         F: FnOnce(&mut TransactionMut<'_>) -> Result<T, NifError>,
     {
@@ -27,8 +50,22 @@ impl DocInner {
             if let Some(txn) = self.current_transaction.borrow_mut().as_mut() {
                 f(txn)
             } else {
-                let mut txn = self.doc.try_transact_mut().unwrap();
-                f(&mut txn)
+                let tracing = self.trace_pid.borrow().is_some();
+                let start = tracing.then(Instant::now);
+                let mut txn = match origin {
+                    Some(origin) => self.doc.try_transact_mut_with(origin).unwrap(),
+                    None => self.doc.try_transact_mut().unwrap(),
+                };
+                let txn_origin = tracing.then(|| txn.origin().map(|o| o.to_string()));
+                let result = f(&mut txn)?;
+                let update_bytes = tracing.then(|| txn.encode_update_v1().len());
+                drop(txn);
+                if let (Some(start), Some(txn_origin), Some(update_bytes)) =
+                    (start, txn_origin, update_bytes)
+                {
+                    self.emit_transaction_event(env, txn_origin, start.elapsed(), update_bytes);
+                }
+                Ok(result)
             }
         })
     }
@@ -44,6 +81,88 @@ impl DocInner {
             f(&txn)
         }
     }
+
+    /// Begins an explicit transaction span, recording the start time so that
+    /// [DocInner::commit_transaction] can report its total duration.
+    pub fn begin_transaction(&self, origin: Option<&str>) {
+        *self.transaction_started_at.borrow_mut() = Some(Instant::now());
+        let txn: TransactionMut = if let Some(origin) = origin {
+            self.doc.try_transact_mut_with(origin).unwrap()
+        } else {
+            self.doc.try_transact_mut().unwrap()
+        };
+        let txn: TransactionMut<'static> = unsafe { std::mem::transmute(txn) };
+        *self.current_transaction.borrow_mut() = Some(txn);
+    }
+
+    /// Closes the currently open explicit transaction (if any) and, when a trace pid is
+    /// configured, emits a telemetry event carrying its origin, duration and applied update
+    /// size.
+    pub fn commit_transaction(&self, env: Env<'_>) {
+        let started_at = self.transaction_started_at.borrow_mut().take();
+        let txn = self.current_transaction.borrow_mut().take();
+        if let Some(txn) = txn {
+            let tracing = self.trace_pid.borrow().is_some();
+            if tracing {
+                let origin = txn.origin().map(|o| o.to_string());
+                let update_bytes = txn.encode_update_v1().len();
+                drop(txn);
+                if let Some(started_at) = started_at {
+                    self.emit_transaction_event(env, origin, started_at.elapsed(), update_bytes);
+                }
+            }
+        }
+    }
+
+    /// Sends a `{:transaction, doc_guid, origin, duration_us, update_bytes}` tuple to the
+    /// configured trace pid, if any. There is deliberately no `gc_enabled`/`gc_ran` field here:
+    /// `yrs`'s public API exposes only the static `skip_gc` option, not whether a given commit
+    /// actually collected anything, and a value that never varies across events would be
+    /// misleading in a time-series telemetry stream. The `trace_pid` borrow is dropped before
+    /// `env.send` so that a receiver calling back into this document cannot re-enter while we
+    /// still hold it.
+    fn emit_transaction_event(
+        &self,
+        env: Env<'_>,
+        origin: Option<String>,
+        duration: Duration,
+        update_bytes: usize,
+    ) {
+        let pid = self.trace_pid.borrow().clone();
+        if let Some(pid) = pid {
+            let guid = self.doc.guid().to_string();
+            let _ = env.send(
+                &pid,
+                (
+                    atoms::transaction(),
+                    guid,
+                    origin,
+                    duration.as_micros() as u64,
+                    update_bytes as u64,
+                ),
+            );
+        }
+    }
+
+    /// Sends a `{:encode_measured, doc_guid, duration_us, bytes}` tuple to the configured trace
+    /// pid, if any. Used by the read-only `encode_state_as_update_*` NIFs, which run through
+    /// [DocInner::readonly] rather than [DocInner::mutably] and so don't go through
+    /// [DocInner::emit_transaction_event].
+    fn emit_encode_event(&self, env: Env<'_>, duration: Duration, bytes: usize) {
+        let pid = self.trace_pid.borrow().clone();
+        if let Some(pid) = pid {
+            let guid = self.doc.guid().to_string();
+            let _ = env.send(
+                &pid,
+                (
+                    atoms::encode_measured(),
+                    guid,
+                    duration.as_micros() as u64,
+                    bytes as u64,
+                ),
+            );
+        }
+    }
 }
 
 #[rustler::resource_impl]
@@ -127,6 +246,8 @@ impl NifDoc {
                 DocInner {
                     doc: Doc::with_options(option.into()),
                     current_transaction: RefCell::new(None),
+                    trace_pid: RefCell::new(None),
+                    transaction_started_at: RefCell::new(None),
                 }
                 .into(),
             ),
@@ -138,6 +259,8 @@ impl NifDoc {
                 DocInner {
                     doc,
                     current_transaction: RefCell::new(None),
+                    trace_pid: RefCell::new(None),
+                    transaction_started_at: RefCell::new(None),
                 }
                 .into(),
             ),
@@ -171,8 +294,12 @@ impl NifDoc {
         )
     }
 
-    pub fn commit_transaction(&self) {
-        *self.reference.current_transaction.borrow_mut() = None;
+    pub fn commit_transaction(&self, env: Env<'_>) {
+        self.reference.commit_transaction(env);
+    }
+
+    pub fn set_trace_pid(&self, pid: LocalPid) {
+        *self.reference.trace_pid.borrow_mut() = Some(pid);
     }
 
     pub fn monitor_update_v1(
@@ -229,6 +356,53 @@ impl NifDoc {
             message: e.to_string(),
         })
     }
+
+    pub fn monitor_subdocs(&self, env: Env<'_>, pid: LocalPid) -> ResourceArc<SubscriptionResource> {
+        ENV.set(&mut env.clone(), || {
+            let doc_ref = self.reference.clone();
+            let sub = self.observe_subdocs(move |_txn, event| {
+                let doc_ref = doc_ref.clone();
+                let summary = NifSubdocsSummary {
+                    added: event.added().map(|d| NifDoc::from_native(d.clone())).collect(),
+                    removed: event.removed().map(|d| NifDoc::from_native(d.clone())).collect(),
+                    loaded: event.loaded().map(|d| NifDoc::from_native(d.clone())).collect(),
+                };
+                ENV.with(|env| {
+                    let _ = env.send(
+                        &pid,
+                        (
+                            atoms::subdocs(),
+                            summary,
+                            NifDoc { reference: doc_ref },
+                        ),
+                    );
+                })
+            });
+            ResourceArc::new(RefCell::new(Some(sub)).into())
+        })
+    }
+
+    pub fn get_subdocs(&self) -> Vec<NifDoc> {
+        self.reference.readonly(|txn| {
+            txn.subdocs()
+                .map(|doc| NifDoc::from_native(doc.clone()))
+                .collect()
+        })
+    }
+
+    pub fn subdoc_guids(&self) -> Vec<String> {
+        self.reference
+            .readonly(|txn| txn.subdocs().map(|doc| doc.guid().to_string()).collect())
+    }
+}
+
+/// Added/removed/loaded subdocuments carried by a `doc_monitor_subdocs` event, each wrapped
+/// as a `Yex.Doc` so the receiving provider process can sync into it directly.
+#[derive(rustler::NifMap)]
+pub struct NifSubdocsSummary {
+    pub added: Vec<NifDoc>,
+    pub removed: Vec<NifDoc>,
+    pub loaded: Vec<NifDoc>,
 }
 
 impl Default for NifDoc {
@@ -238,6 +412,8 @@ impl Default for NifDoc {
                 DocInner {
                     doc: Doc::new(),
                     current_transaction: RefCell::new(None),
+                    trace_pid: RefCell::new(None),
+                    transaction_started_at: RefCell::new(None),
                 }
                 .into(),
             ),
@@ -285,20 +461,20 @@ fn doc_get_or_insert_xml_fragment(env: Env<'_>, doc: NifDoc, name: &str) -> NifX
 
 #[rustler::nif]
 fn doc_begin_transaction(doc: NifDoc, origin: Option<&str>) {
-    if let Some(origin) = origin {
-        let txn: TransactionMut = doc.reference.doc.try_transact_mut_with(origin).unwrap();
-        let txn: TransactionMut<'static> = unsafe { std::mem::transmute(txn) };
-        *doc.reference.current_transaction.borrow_mut() = Some(txn);
-    } else {
-        let txn: TransactionMut = doc.reference.doc.try_transact_mut().unwrap();
-        let txn: TransactionMut<'static> = unsafe { std::mem::transmute(txn) };
-        *doc.reference.current_transaction.borrow_mut() = Some(txn);
-    }
+    doc.reference.begin_transaction(origin);
 }
 
 #[rustler::nif]
 fn doc_commit_transaction(env: Env<'_>, doc: NifDoc) {
-    ENV.set(&mut env.clone(), || doc.commit_transaction())
+    ENV.set(&mut env.clone(), || doc.commit_transaction(env))
+}
+
+/// Sends transaction telemetry for this document to `pid`, much like `doc_monitor_update_v1`
+/// does for raw update bytes. Call with a new pid to redirect measurements, e.g. when handing
+/// a hot document off to a different monitoring process.
+#[rustler::nif]
+fn doc_set_trace_pid(doc: NifDoc, pid: LocalPid) {
+    doc.set_trace_pid(pid);
 }
 
 #[rustler::nif]
@@ -316,6 +492,25 @@ fn doc_monitor_update_v2(
     doc.monitor_update_v2(pid)
 }
 
+#[rustler::nif]
+fn doc_monitor_subdocs(
+    env: Env<'_>,
+    doc: NifDoc,
+    pid: LocalPid,
+) -> ResourceArc<SubscriptionResource> {
+    doc.monitor_subdocs(env, pid)
+}
+
+#[rustler::nif]
+fn doc_get_subdocs(doc: NifDoc) -> Vec<NifDoc> {
+    doc.get_subdocs()
+}
+
+#[rustler::nif]
+fn doc_subdoc_guids(doc: NifDoc) -> Vec<String> {
+    doc.subdoc_guids()
+}
+
 #[rustler::nif]
 fn apply_update_v1(env: Env<'_>, doc: NifDoc, update: Binary) -> Result<(), NifError> {
     let update = Update::decode_v1(update.as_slice()).map_err(|e| NifError {
@@ -365,9 +560,13 @@ fn encode_state_as_update_v1<'a>(
         StateVector::default()
     };
 
+    let start = Instant::now();
     doc.reference
         .readonly(|txn| Ok(txn.encode_diff_v1(&sv)))
-        .map(|vec| encode_binary_slice_to_term(env, vec.as_slice()))
+        .map(|vec: Vec<u8>| {
+            doc.reference.emit_encode_event(env, start.elapsed(), vec.len());
+            encode_binary_slice_to_term(env, vec.as_slice())
+        })
 }
 
 #[rustler::nif]
@@ -390,7 +589,107 @@ fn encode_state_as_update_v2<'a>(
         StateVector::default()
     };
 
+    let start = Instant::now();
     doc.reference
         .readonly(|txn| Ok(txn.encode_diff_v2(&sv)))
-        .map(|vec| encode_binary_slice_to_term(env, vec.as_slice()))
+        .map(|vec: Vec<u8>| {
+            doc.reference.emit_encode_event(env, start.elapsed(), vec.len());
+            encode_binary_slice_to_term(env, vec.as_slice())
+        })
+}
+
+/// An encoded `yrs::Snapshot`, the state vector plus delete set that identifies a document's
+/// state at the moment `doc_take_snapshot` was called.
+#[derive(NifStruct)]
+#[module = "Yex.Snapshot"]
+pub struct NifSnapshot {
+    data: Vec<u8>,
+}
+
+impl NifSnapshot {
+    fn decode(&self) -> Result<Snapshot, NifError> {
+        Snapshot::decode_v1(&self.data).map_err(|e| NifError {
+            reason: atoms::encoding_exception(),
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Snapshots only make sense for documents that retain tombstones: once `skip_gc` is `false`
+/// (the default), `yrs` is free to collect deleted content, at which point a snapshot can no
+/// longer be taken or replayed. Shared by `doc_take_snapshot` and
+/// `doc_encode_state_from_snapshot` so both NIFs reject GC-enabled docs the same way instead of
+/// panicking or silently dropping content inside `yrs`.
+fn require_skip_gc(doc: &NifDoc) -> Result<(), NifError> {
+    if !doc.reference.doc.options().skip_gc {
+        return Err(NifError {
+            reason: atoms::error(),
+            message: "snapshots require the document to be created with skip_gc: true".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[rustler::nif]
+fn doc_take_snapshot(doc: NifDoc) -> Result<NifSnapshot, NifError> {
+    require_skip_gc(&doc)?;
+
+    let snapshot = doc.reference.readonly(|txn| txn.snapshot());
+    Ok(NifSnapshot {
+        data: snapshot.encode_v1(),
+    })
+}
+
+#[rustler::nif]
+fn doc_encode_state_from_snapshot<'a>(
+    env: Env<'a>,
+    doc: NifDoc,
+    snapshot: NifSnapshot,
+) -> Result<Term<'a>, NifError> {
+    require_skip_gc(&doc)?;
+    let snapshot = snapshot.decode()?;
+
+    doc.reference
+        .readonly(|txn| {
+            let mut encoder = EncoderV1::new();
+            txn.encode_state_from_snapshot(&snapshot, &mut encoder)
+                .map(|_| encoder.to_vec())
+                .map_err(|e| NifError {
+                    reason: atoms::encoding_exception(),
+                    message: e.to_string(),
+                })
+        })
+        .map(|bytes| encode_binary_slice_to_term(env, bytes.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn require_skip_gc_rejects_docs_that_collect_garbage() {
+        let doc = NifDoc::default();
+        let err = require_skip_gc(&doc).unwrap_err();
+        assert_eq!(err.reason, atoms::error());
+    }
+
+    #[test]
+    fn require_skip_gc_allows_docs_created_with_skip_gc() {
+        let doc = NifDoc::with_options(NifOptions {
+            client_id: 1,
+            guid: None,
+            collection_id: None,
+            offset_kind: NifOffsetKind::Bytes,
+            skip_gc: true,
+            auto_load: false,
+            should_load: true,
+        });
+        assert!(require_skip_gc(&doc).is_ok());
+    }
+
+    #[test]
+    fn doc_take_snapshot_rejects_garbage_collecting_docs() {
+        let doc = NifDoc::default();
+        assert!(doc_take_snapshot(doc).is_err());
+    }
 }
@@ -0,0 +1,92 @@
+use crate::{atoms, doc::NifDoc, wrap::encode_binary_slice_to_term, NifError};
+use rustler::{Binary, Env, Term};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{ReadTxn, StateVector, Update};
+
+/// Step 1 of the Yjs sync protocol: the local state vector to send to a peer so it can
+/// compute the diff we're missing.
+#[rustler::nif]
+fn sync_step1(env: Env<'_>, doc: NifDoc) -> Term<'_> {
+    let sv = doc.reference.readonly(|txn| txn.state_vector().encode_v1());
+    encode_binary_slice_to_term(env, sv.as_slice())
+}
+
+/// Step 2 of the Yjs sync protocol: given a peer's state vector, the update containing
+/// everything they're missing.
+#[rustler::nif]
+fn sync_step2<'a>(
+    env: Env<'a>,
+    doc: NifDoc,
+    remote_state_vector: Binary,
+) -> Result<Term<'a>, NifError> {
+    let sv = StateVector::decode_v1(remote_state_vector.as_slice()).map_err(|e| NifError {
+        reason: atoms::encoding_exception(),
+        message: e.to_string(),
+    })?;
+
+    let diff = doc.reference.readonly(|txn| txn.encode_diff_v1(&sv));
+    Ok(encode_binary_slice_to_term(env, diff.as_slice()))
+}
+
+/// Applies a peer's sync-step-2 update and, in the same transaction, computes our own
+/// outgoing diff against their state vector - avoiding the double-transaction cost of
+/// calling `apply_update_v1` followed by a separate `encode_state_as_update_v1`.
+#[rustler::nif]
+fn sync_step1_reply<'a>(
+    env: Env<'a>,
+    doc: NifDoc,
+    remote_update: Binary,
+    remote_state_vector: Binary,
+    origin: Option<&str>,
+) -> Result<Term<'a>, NifError> {
+    let update = Update::decode_v1(remote_update.as_slice()).map_err(|e| NifError {
+        reason: atoms::encoding_exception(),
+        message: e.to_string(),
+    })?;
+    let sv = StateVector::decode_v1(remote_state_vector.as_slice()).map_err(|e| NifError {
+        reason: atoms::encoding_exception(),
+        message: e.to_string(),
+    })?;
+
+    let diff = doc.reference.mutably_with_origin(env, origin, |txn| {
+        txn.apply_update(update);
+        Ok(txn.encode_diff_v1(&sv))
+    })?;
+
+    Ok(encode_binary_slice_to_term(env, diff.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{Doc, Text, Transact};
+
+    /// `sync_step1_reply` applies the peer's update and encodes our diff against their state
+    /// vector inside the same transaction, rather than committing the apply and opening a
+    /// second transaction to diff afterwards. The diff it produces must already reflect the
+    /// just-applied update - identical to the diff a fresh, post-commit transaction would
+    /// compute - or callers would end up one round-trip behind.
+    #[test]
+    fn diff_computed_in_the_apply_transaction_matches_a_post_commit_diff() {
+        let local = Doc::new();
+        {
+            let text = local.get_or_insert_text("content");
+            let mut txn = local.transact_mut();
+            text.insert(&mut txn, 0, "hello");
+        }
+        let local_update = local.transact().encode_diff_v1(&StateVector::default());
+
+        let remote = Doc::new();
+        let remote_sv = remote.transact().state_vector();
+
+        let mut txn = remote.transact_mut();
+        txn.apply_update(Update::decode_v1(&local_update).unwrap());
+        let diff_in_same_txn = txn.encode_diff_v1(&remote_sv);
+        drop(txn);
+
+        let post_commit_diff = remote.transact().encode_diff_v1(&remote_sv);
+        assert!(!diff_in_same_txn.is_empty());
+        assert_eq!(diff_in_same_txn, post_commit_diff);
+    }
+}
@@ -0,0 +1,237 @@
+use std::cell::RefCell;
+
+use crate::{
+    atoms,
+    doc::NifDoc,
+    subscription::SubscriptionResource,
+    wrap::NifWrap,
+    xml::NifXmlFragment,
+    NifArray, NifError, NifMap, NifText, ENV,
+};
+use rustler::{Env, LocalPid, NifStruct, NifUnitEnum, NifUntaggedEnum, ResourceArc};
+use yrs::undo::UndoManager;
+
+/// Any of the shared root types returned by `Yex.Doc.get_or_insert_*`. An `UndoManager` is
+/// scoped to one of these so that only edits under that root end up on its undo stack.
+#[derive(NifUntaggedEnum)]
+pub enum NifUndoScope {
+    Text(NifText),
+    Array(NifArray),
+    Map(NifMap),
+    XmlFragment(NifXmlFragment),
+}
+
+pub type UndoManagerResource = NifWrap<RefCell<UndoManager<()>>>;
+#[rustler::resource_impl]
+impl rustler::Resource for UndoManagerResource {}
+
+#[derive(NifUnitEnum)]
+pub enum NifEventKind {
+    Undo,
+    Redo,
+}
+
+/// Metadata describing the stack item a `undo_manager_monitor_item_added`/`_item_popped`
+/// event fired for: whether it was pushed onto the undo or redo stack, and how many
+/// insertions/deletions it captures.
+#[derive(rustler::NifMap)]
+pub struct NifUndoItemMeta {
+    pub kind: NifEventKind,
+    pub insertions: u32,
+    pub deletions: u32,
+}
+
+fn item_meta(event: &yrs::undo::Event<()>) -> NifUndoItemMeta {
+    NifUndoItemMeta {
+        kind: match event.kind() {
+            yrs::undo::EventKind::Undo => NifEventKind::Undo,
+            yrs::undo::EventKind::Redo => NifEventKind::Redo,
+        },
+        insertions: event.insertions().len() as u32,
+        deletions: event.deletions().len() as u32,
+    }
+}
+
+#[derive(NifStruct)]
+#[module = "Yex.UndoManager"]
+pub struct NifUndoManager {
+    reference: ResourceArc<UndoManagerResource>,
+}
+
+#[rustler::nif]
+fn undo_manager_new(doc: NifDoc, scope: NifUndoScope) -> NifUndoManager {
+    let manager = match scope {
+        NifUndoScope::Text(text) => UndoManager::new(&doc, &text),
+        NifUndoScope::Array(array) => UndoManager::new(&doc, &array),
+        NifUndoScope::Map(map) => UndoManager::new(&doc, &map),
+        NifUndoScope::XmlFragment(xml) => UndoManager::new(&doc, &xml),
+    };
+    NifUndoManager {
+        reference: ResourceArc::new(RefCell::new(manager).into()),
+    }
+}
+
+/// Restricts this manager to capturing only transactions whose origin matches one of the
+/// tracked origins, the same origin string passed to `doc_begin_transaction`. Without any
+/// tracked origin the manager captures only transactions that carry no origin; transactions
+/// tagged with an origin are not captured.
+#[rustler::nif]
+fn undo_manager_include_origin(undo_manager: NifUndoManager, origin: &str) {
+    undo_manager
+        .reference
+        .borrow_mut()
+        .include_origin(origin);
+}
+
+#[rustler::nif]
+fn undo_manager_exclude_origin(undo_manager: NifUndoManager, origin: &str) {
+    undo_manager
+        .reference
+        .borrow_mut()
+        .exclude_origin(origin);
+}
+
+#[rustler::nif]
+fn undo_manager_undo(env: Env<'_>, undo_manager: NifUndoManager) -> Result<bool, NifError> {
+    ENV.set(&mut env.clone(), || {
+        undo_manager
+            .reference
+            .borrow_mut()
+            .undo()
+            .map_err(|e| NifError {
+                reason: atoms::error(),
+                message: e.to_string(),
+            })
+    })
+}
+
+#[rustler::nif]
+fn undo_manager_redo(env: Env<'_>, undo_manager: NifUndoManager) -> Result<bool, NifError> {
+    ENV.set(&mut env.clone(), || {
+        undo_manager
+            .reference
+            .borrow_mut()
+            .redo()
+            .map_err(|e| NifError {
+                reason: atoms::error(),
+                message: e.to_string(),
+            })
+    })
+}
+
+#[rustler::nif]
+fn undo_manager_can_undo(undo_manager: NifUndoManager) -> bool {
+    undo_manager.reference.borrow().can_undo()
+}
+
+#[rustler::nif]
+fn undo_manager_can_redo(undo_manager: NifUndoManager) -> bool {
+    undo_manager.reference.borrow().can_redo()
+}
+
+/// Ends the current undo-capture batch early, so that the next local change starts a new
+/// stack item instead of being merged into the previous one.
+#[rustler::nif]
+fn undo_manager_stop_capturing(undo_manager: NifUndoManager) {
+    undo_manager.reference.borrow_mut().stop_capturing();
+}
+
+#[rustler::nif]
+fn undo_manager_clear(env: Env<'_>, undo_manager: NifUndoManager) -> Result<(), NifError> {
+    ENV.set(&mut env.clone(), || {
+        undo_manager
+            .reference
+            .borrow_mut()
+            .clear()
+            .map_err(|e| NifError {
+                reason: atoms::error(),
+                message: e.to_string(),
+            })
+    })
+}
+
+#[rustler::nif]
+fn undo_manager_monitor_item_added(
+    env: Env<'_>,
+    undo_manager: NifUndoManager,
+    pid: LocalPid,
+) -> ResourceArc<SubscriptionResource> {
+    ENV.set(&mut env.clone(), || {
+        let sub = undo_manager
+            .reference
+            .borrow_mut()
+            .observe_item_added(move |_txn, event| {
+                let origin = event.origin().map(|o| o.to_string());
+                let meta = item_meta(event);
+                ENV.with(|env| {
+                    let _ = env.send(&pid, (atoms::stack_item_added(), origin, meta));
+                })
+            });
+        ResourceArc::new(RefCell::new(Some(sub)).into())
+    })
+}
+
+#[rustler::nif]
+fn undo_manager_monitor_item_popped(
+    env: Env<'_>,
+    undo_manager: NifUndoManager,
+    pid: LocalPid,
+) -> ResourceArc<SubscriptionResource> {
+    ENV.set(&mut env.clone(), || {
+        let sub = undo_manager
+            .reference
+            .borrow_mut()
+            .observe_item_popped(move |_txn, event| {
+                let origin = event.origin().map(|o| o.to_string());
+                let meta = item_meta(event);
+                ENV.with(|env| {
+                    let _ = env.send(&pid, (atoms::stack_item_popped(), origin, meta));
+                })
+            });
+        ResourceArc::new(RefCell::new(Some(sub)).into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{Text, Transact};
+
+    /// `undo_manager_include_origin` should scope the manager so only transactions tagged
+    /// with a tracked origin become undoable stack items; edits under other origins are
+    /// observed by the doc but never pushed onto this manager's stack.
+    #[test]
+    fn undo_manager_only_tracks_included_origin_edits() {
+        let doc = NifDoc::default();
+        let text = doc.get_or_insert_text("content");
+        let manager = undo_manager_new(
+            NifDoc {
+                reference: doc.reference.clone(),
+            },
+            NifUndoScope::Text(text),
+        );
+        undo_manager_include_origin(
+            NifUndoManager {
+                reference: manager.reference.clone(),
+            },
+            "alice",
+        );
+
+        let raw_text = doc.reference.doc.get_or_insert_text("content");
+        {
+            let mut txn = doc.reference.doc.try_transact_mut_with("bob").unwrap();
+            raw_text.insert(&mut txn, 0, "untracked");
+        }
+        assert!(!undo_manager_can_undo(NifUndoManager {
+            reference: manager.reference.clone(),
+        }));
+
+        {
+            let mut txn = doc.reference.doc.try_transact_mut_with("alice").unwrap();
+            raw_text.insert(&mut txn, 0, "tracked");
+        }
+        assert!(undo_manager_can_undo(NifUndoManager {
+            reference: manager.reference.clone(),
+        }));
+    }
+}